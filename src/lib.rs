@@ -1,15 +1,100 @@
 use nih_plug::prelude::*;
 use nih_plug_egui::{create_egui_editor, egui, widgets, EguiState};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
 /// The time it takes for the peak meter to decay by 12 dB after switching to complete silence.
 const PEAK_METER_DECAY_MS: f64 = 150.0;
 
+/// Polyphase FIR used to 4x-oversample the signal for inter-sample (true-peak) detection. Each row
+/// is a 4-tap fractional-delay interpolator (applied to `[x[n], x[n-1], x[n-2], x[n-3]]`) that
+/// reconstructs one of the four sub-sample positions between consecutive input samples. The taps
+/// are windowed-sinc kernels, each normalized to unity DC gain.
+const TRUE_PEAK_FIR: [[f32; 4]; 4] = [
+    [-0.01793722, 0.51793722, 0.51793722, -0.01793722],
+    [-0.0160122, 0.77058711, 0.25686237, -0.011437286],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.040927694, 1.1817872, -0.23635744, 0.013642565],
+];
+
+/// Integration time of the VU / K-system RMS window. The classic VU movement uses a ~300 ms
+/// response, which we approximate with a one-pole average of the squared signal.
+const RMS_INTEGRATION_MS: f64 = 300.0;
+
+/// The metering standard used to display the level. This only changes the displayed ballistics and
+/// scaling, never the audio itself.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum MeterMode {
+    /// Sample-accurate digital peak, read out in dBFS.
+    #[id = "peak"]
+    #[name = "Digital Peak"]
+    DigitalPeak,
+    /// Slow RMS ballistics with the traditional VU response.
+    #[id = "vu"]
+    #[name = "VU"]
+    Vu,
+    /// K-20 RMS metering (0 on the scale is at -20 dBFS).
+    #[id = "k20"]
+    #[name = "K-20"]
+    K20,
+    /// K-14 RMS metering (0 on the scale is at -14 dBFS).
+    #[id = "k14"]
+    #[name = "K-14"]
+    K14,
+    /// K-12 RMS metering (0 on the scale is at -12 dBFS).
+    #[id = "k12"]
+    #[name = "K-12"]
+    K12,
+}
+
+impl MeterMode {
+    /// The dBFS offset that maps onto the "0" line for K-system modes, or `None` for the plain
+    /// dBFS-referenced modes.
+    fn k_reference_db(self) -> Option<f32> {
+        match self {
+            MeterMode::K20 => Some(20.0),
+            MeterMode::K14 => Some(14.0),
+            MeterMode::K12 => Some(12.0),
+            MeterMode::DigitalPeak | MeterMode::Vu => None,
+        }
+    }
+}
+
+/// Map a meter reading in dB onto the `[0, 1]` range used by the `ProgressBar`, taking the meter
+/// mode's reference level into account.
+fn meter_normalized(mode: MeterMode, db: f32) -> f32 {
+    match mode.k_reference_db() {
+        // On a K-scale the reading is shown relative to the reference, with ~40 dB below and
+        // 20 dB above the "0" line.
+        Some(reference) => (((db + reference) + 40.0) / 60.0).clamp(0.0, 1.0),
+        None => ((db + 60.0) / 60.0).clamp(0.0, 1.0),
+    }
+}
+
+/// Unit label for the readout of a given meter mode.
+fn meter_unit(mode: MeterMode) -> &'static str {
+    match mode {
+        MeterMode::DigitalPeak => "dBFS",
+        MeterMode::Vu => "VU",
+        MeterMode::K20 => "K-20",
+        MeterMode::K14 => "K-14",
+        MeterMode::K12 => "K-12",
+    }
+}
+
 struct RingModSideChain {
     params: Arc<RingModSideChainParams>,
 
     /// Needed to normalize the peak meter's response based on the sample rate.
     peak_meter_decay_weight: f32,
+    /// One-pole coefficient for the VU / K-system RMS integrator, derived from the sample rate.
+    rms_integration_weight: f32,
+    /// Running mean-square of the main and side-chain signals for the RMS-based meter modes.
+    rms_state: f32,
+    side_chain_rms_state: f32,
+    /// Per-channel history of the last three output samples, feeding the true-peak oversampler so
+    /// the FIR stays continuous across processing blocks. Allocated in [`Plugin::initialize`].
+    true_peak_history: Vec<[f32; 3]>,
     /// The current data for the peak meter. This is stored as an [`Arc`] so we can share it between
     /// the GUI and the audio processing parts. If you have more state to share, then it's a good
     /// idea to put all of that in a struct behind a single `Arc`.
@@ -17,6 +102,14 @@ struct RingModSideChain {
     /// This is stored as voltage gain.
     peak_meter: Arc<AtomicF32>,
     side_chain_peak_meter: Arc<AtomicF32>,
+
+    /// The highest peak seen since the last reset. Unlike [`Self::peak_meter`] these never decay
+    /// on their own, giving a "peak hold" readout of the true ceiling of a pass.
+    max_peak_meter: Arc<AtomicF32>,
+    side_chain_max_peak_meter: Arc<AtomicF32>,
+    /// Set from the GUI to request that the latched maxima be cleared. Checked and cleared at the
+    /// start of each processing block so the reset is safe across threads.
+    reset_max_peak: Arc<AtomicBool>,
 }
 
 #[derive(Params)]
@@ -32,9 +125,14 @@ struct RingModSideChainParams {
     #[id = "side_chain_gain"]
     pub side_chain_gain: FloatParam,
 
-    // TODO: Remove this parameter when we're done implementing the widgets
-    #[id = "foobar"]
-    pub some_int: IntParam,
+    #[id = "mix"]
+    pub mix: FloatParam,
+
+    #[id = "meter_mode"]
+    pub meter_mode: EnumParam<MeterMode>,
+
+    #[id = "true_peak"]
+    pub true_peak: BoolParam,
 }
 
 impl Default for RingModSideChain {
@@ -43,8 +141,16 @@ impl Default for RingModSideChain {
             params: Arc::new(RingModSideChainParams::default()),
 
             peak_meter_decay_weight: 1.0,
+            rms_integration_weight: 1.0,
+            rms_state: 0.0,
+            side_chain_rms_state: 0.0,
+            true_peak_history: Vec::new(),
             peak_meter: Arc::new(AtomicF32::new(util::MINUS_INFINITY_DB)),
             side_chain_peak_meter: Arc::new(AtomicF32::new(util::MINUS_INFINITY_DB)), // Initialize side chain peak meter
+
+            max_peak_meter: Arc::new(AtomicF32::new(util::MINUS_INFINITY_DB)),
+            side_chain_max_peak_meter: Arc::new(AtomicF32::new(util::MINUS_INFINITY_DB)),
+            reset_max_peak: Arc::new(AtomicBool::new(false)),
         }
     }
 }
@@ -81,22 +187,58 @@ impl Default for RingModSideChainParams {
             .with_unit(" dB")
             .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
             .with_string_to_value(formatters::s2v_f32_gain_to_db()),
-            some_int: IntParam::new("Something", 3, IntRange::Linear { min: 0, max: 3 }),
+            mix: FloatParam::new("Mix", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_unit(" %")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            meter_mode: EnumParam::new("Meter Mode", MeterMode::DigitalPeak),
+            true_peak: BoolParam::new("True Peak", false),
         }
     }
 }
 
 // Move this function outside of the impl block
-fn add_peak_meter_ui(ui: &mut egui::Ui, meter: &Arc<AtomicF32>) {
+fn add_peak_meter_ui(
+    ui: &mut egui::Ui,
+    mode: MeterMode,
+    true_peak: bool,
+    meter: &Arc<AtomicF32>,
+    max_meter: &Arc<AtomicF32>,
+) {
+    // The digital-peak readout turns into a true-peak ("dBTP") readout when oversampling is on.
+    let unit = if true_peak && mode == MeterMode::DigitalPeak {
+        "dBTP"
+    } else {
+        meter_unit(mode)
+    };
     let peak_meter = util::gain_to_db(meter.load(std::sync::atomic::Ordering::Relaxed));
     let peak_meter_text = if peak_meter > util::MINUS_INFINITY_DB {
-        format!("{peak_meter:.1} dBFS")
+        format!("{peak_meter:.1} {unit}")
     } else {
-        String::from("-inf dBFS")
+        format!("-inf {unit}")
     };
 
-    let peak_meter_normalized = (peak_meter + 60.0) / 60.0;
-    ui.add(egui::widgets::ProgressBar::new(peak_meter_normalized).text(peak_meter_text));
+    let peak_meter_normalized = meter_normalized(mode, peak_meter);
+    let response =
+        ui.add(egui::widgets::ProgressBar::new(peak_meter_normalized).text(peak_meter_text));
+
+    // Overlay a thin marker line at the held maximum so the clip ceiling stays visible even after
+    // the live meter has decayed away.
+    let max_peak = util::gain_to_db(max_meter.load(std::sync::atomic::Ordering::Relaxed));
+    let max_peak_text = if max_peak > util::MINUS_INFINITY_DB {
+        let max_normalized = meter_normalized(mode, max_peak);
+        let rect = response.rect;
+        let x = rect.left() + rect.width() * max_normalized;
+        ui.painter().line_segment(
+            [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())],
+            egui::Stroke::new(1.0, egui::Color32::YELLOW),
+        );
+        format!("max: {max_peak:.1} {unit}")
+    } else {
+        format!("max: -inf {unit}")
+    };
+    ui.label(max_peak_text);
 }
 
 impl Plugin for RingModSideChain {
@@ -144,6 +286,9 @@ impl Plugin for RingModSideChain {
         let params = self.params.clone();
         let peak_meter = self.peak_meter.clone();
         let side_chain_peak_meter = self.side_chain_peak_meter.clone();
+        let max_peak_meter = self.max_peak_meter.clone();
+        let side_chain_max_peak_meter = self.side_chain_max_peak_meter.clone();
+        let reset_max_peak = self.reset_max_peak.clone();
         create_egui_editor(
             self.params.editor_state.clone(),
             (),
@@ -161,15 +306,39 @@ impl Plugin for RingModSideChain {
                         setter,
                     ));
 
+                    ui.label("Mix");
+                    ui.add(widgets::ParamSlider::for_param(&params.mix, setter));
+
+                    ui.label("Meter Mode");
+                    ui.add(widgets::ParamSlider::for_param(&params.meter_mode, setter));
+
+                    ui.label("True Peak");
+                    ui.add(widgets::ParamSlider::for_param(&params.true_peak, setter));
+
+                    let meter_mode = params.meter_mode.value();
+                    let true_peak = params.true_peak.value();
+
                     ui.group(|ui| {
                         ui.label("Main Peak Meter");
-                        add_peak_meter_ui(ui, &peak_meter);
+                        add_peak_meter_ui(ui, meter_mode, true_peak, &peak_meter, &max_peak_meter);
                     });
 
                     ui.group(|ui| {
                         ui.label("Side Chain Peak Meter");
-                        add_peak_meter_ui(ui, &side_chain_peak_meter);
+                        // Only the main output is oversampled, so the side-chain readout stays a
+                        // plain sample peak and is never labelled as true-peak.
+                        add_peak_meter_ui(
+                            ui,
+                            meter_mode,
+                            false,
+                            &side_chain_peak_meter,
+                            &side_chain_max_peak_meter,
+                        );
                     });
+
+                    if ui.button("Reset Max Peak").clicked() {
+                        reset_max_peak.store(true, std::sync::atomic::Ordering::Relaxed);
+                    }
                 });
             },
         )
@@ -177,7 +346,7 @@ impl Plugin for RingModSideChain {
 
     fn initialize(
         &mut self,
-        _audio_io_layout: &AudioIOLayout,
+        audio_io_layout: &AudioIOLayout,
         buffer_config: &BufferConfig,
         _context: &mut impl InitContext<Self>,
     ) -> bool {
@@ -187,44 +356,146 @@ impl Plugin for RingModSideChain {
             .powf((buffer_config.sample_rate as f64 * PEAK_METER_DECAY_MS / 1000.0).recip())
             as f32;
 
+        // One-pole coefficient for a ~`RMS_INTEGRATION_MS` integration window.
+        self.rms_integration_weight =
+            (-1.0 / (buffer_config.sample_rate as f64 * RMS_INTEGRATION_MS / 1000.0)).exp() as f32;
+
+        // Allocate the true-peak history once, sized to the main bus, so the audio thread never has
+        // to.
+        let num_channels = audio_io_layout
+            .main_input_channels
+            .map(NonZeroU32::get)
+            .unwrap_or(0) as usize;
+        self.true_peak_history = vec![[0.0; 3]; num_channels];
+
         true
     }
 
     fn reset(&mut self) {
-        // Reset buffers and envelopes here. This can be called from the audio thread and may not
-        // allocate. You can remove this function if you do not need it.
+        // The RMS integrators and the true-peak filter history carry state across blocks, so they
+        // need to be cleared when the host reinitializes the plugin.
+        self.rms_state = 0.0;
+        self.side_chain_rms_state = 0.0;
+        for history in &mut self.true_peak_history {
+            *history = [0.0; 3];
+        }
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
+        aux: &mut AuxiliaryBuffers,
         _context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
-        for channel_samples in buffer.iter_samples() {
-            let mut amplitude = 0.0;
-            let num_samples = channel_samples.len();
+        // Honour a reset requested from the GUI before touching the latched maxima this block.
+        if self.reset_max_peak.swap(false, std::sync::atomic::Ordering::Relaxed) {
+            self.max_peak_meter
+                .store(util::MINUS_INFINITY_DB, std::sync::atomic::Ordering::Relaxed);
+            self.side_chain_max_peak_meter
+                .store(util::MINUS_INFINITY_DB, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        // The side-chain carrier lives in the first aux input. With the second
+        // `AudioIOLayout` there is no aux bus, in which case the carrier is treated as unity so
+        // the signal simply passes through (scaled by the gains).
+        let carrier = aux.inputs.first().map(|b| b.as_slice_immutable());
+
+        // True-peak oversampling is only worth its CPU for the digital-peak readout while the GUI
+        // is visible.
+        let true_peak_active = self.params.editor_state.is_open()
+            && self.params.true_peak.value()
+            && self.params.meter_mode.value() == MeterMode::DigitalPeak;
+
+        for (sample_idx, channel_samples) in buffer.iter_samples().enumerate() {
+            let mut peak = 0.0f32;
+            let mut side_chain_peak = 0.0f32;
+            let mut sum_squares = 0.0f32;
+            let mut side_chain_sum_squares = 0.0f32;
+            let num_channels = channel_samples.len();
 
             let gain = self.params.gain.smoothed.next();
-            for sample in channel_samples {
-                *sample *= gain;
-                amplitude += *sample;
+            let side_chain_gain = self.params.side_chain_gain.smoothed.next();
+            let mix = self.params.mix.smoothed.next();
+
+            for (channel_idx, sample) in channel_samples.into_iter().enumerate() {
+                let dry = *sample;
+
+                // Fall back to the first carrier channel when the aux buffer has fewer channels
+                // than the main buffer (mono side-chain fed into a stereo effect).
+                let carrier = carrier
+                    .as_ref()
+                    .map(|sc| {
+                        let carrier_channel = if channel_idx < sc.len() {
+                            channel_idx
+                        } else {
+                            0
+                        };
+                        sc[carrier_channel][sample_idx]
+                    })
+                    .unwrap_or(1.0);
+
+                let ring_mod = dry * gain * (carrier * side_chain_gain);
+                *sample = mix * ring_mod + (1.0 - mix) * dry;
+
+                // Read the actual inter-sample peaks by 4x-oversampling the output through the
+                // polyphase FIR before taking the maximum, keeping the filter history continuous.
+                if true_peak_active {
+                    if let Some(history) = self.true_peak_history.get_mut(channel_idx) {
+                        for taps in &TRUE_PEAK_FIR {
+                            let interpolated = taps[0] * *sample
+                                + taps[1] * history[0]
+                                + taps[2] * history[1]
+                                + taps[3] * history[2];
+                            peak = peak.max(interpolated.abs());
+                        }
+                        *history = [*sample, history[0], history[1]];
+                    }
+                }
+
+                // The instantaneous peak is the largest absolute value across all channels of this
+                // single sample frame.
+                peak = peak.max(sample.abs());
+                side_chain_peak = side_chain_peak.max(carrier.abs());
+                sum_squares += *sample * *sample;
+                side_chain_sum_squares += carrier * carrier;
             }
 
             // To save resources, a plugin can (and probably should!) only perform expensive
-            // calculations that are only displayed on the GUI while the GUI is open
+            // calculations that are only displayed on the GUI while the GUI is open. The meter is
+            // advanced once per sample frame so its ballistics are independent of the host's block
+            // size.
             if self.params.editor_state.is_open() {
-                amplitude = (amplitude / num_samples as f32).abs();
-                let current_peak_meter = self.peak_meter.load(std::sync::atomic::Ordering::Relaxed);
-                let new_peak_meter = if amplitude > current_peak_meter {
-                    amplitude
-                } else {
-                    current_peak_meter * self.peak_meter_decay_weight
-                        + amplitude * (1.0 - self.peak_meter_decay_weight)
-                };
-
-                self.peak_meter
-                    .store(new_peak_meter, std::sync::atomic::Ordering::Relaxed)
+                match self.params.meter_mode.value() {
+                    MeterMode::DigitalPeak => {
+                        self.update_peak_meter(peak, &self.peak_meter, &self.max_peak_meter);
+                        self.update_peak_meter(
+                            side_chain_peak,
+                            &self.side_chain_peak_meter,
+                            &self.side_chain_max_peak_meter,
+                        );
+                    }
+                    // VU and all K-system modes share the same windowed-RMS integrator; the K
+                    // modes only differ in how the result is scaled and labelled on the GUI.
+                    _ => {
+                        let channels = num_channels as f32;
+                        let weight = self.rms_integration_weight;
+                        self.rms_state =
+                            self.rms_state * weight + (sum_squares / channels) * (1.0 - weight);
+                        self.side_chain_rms_state = self.side_chain_rms_state * weight
+                            + (side_chain_sum_squares / channels) * (1.0 - weight);
+
+                        self.store_rms_meter(
+                            self.rms_state.sqrt(),
+                            &self.peak_meter,
+                            &self.max_peak_meter,
+                        );
+                        self.store_rms_meter(
+                            self.side_chain_rms_state.sqrt(),
+                            &self.side_chain_peak_meter,
+                            &self.side_chain_max_peak_meter,
+                        );
+                    }
+                }
             }
         }
 
@@ -233,31 +504,34 @@ impl Plugin for RingModSideChain {
 }
 
 impl RingModSideChain {
-    #[allow(dead_code)]
-    fn update_peak_meter(&self, amplitude: f32, num_samples: usize, meter: &Arc<AtomicF32>) {
-        let amplitude = (amplitude / num_samples as f32).abs();
+    /// Feed a single sample frame's instantaneous `peak` into the shared meter. The stored value
+    /// jumps up immediately when a louder peak arrives and otherwise decays by
+    /// `peak_meter_decay_weight` per sample, so the fall-off rate only depends on the sample rate
+    /// and not on the host's block size.
+    fn update_peak_meter(&self, peak: f32, meter: &Arc<AtomicF32>, max_meter: &Arc<AtomicF32>) {
         let current_peak_meter = meter.load(std::sync::atomic::Ordering::Relaxed);
-        let new_peak_meter = if amplitude > current_peak_meter {
-            amplitude
+        let new_peak_meter = if peak > current_peak_meter {
+            peak
         } else {
             current_peak_meter * self.peak_meter_decay_weight
-                + amplitude * (1.0 - self.peak_meter_decay_weight)
         };
 
         meter.store(new_peak_meter, std::sync::atomic::Ordering::Relaxed);
-    }
 
-    #[allow(dead_code)]
-    fn add_peak_meter_ui(&self, ui: &mut egui::Ui, meter: &Arc<AtomicF32>) {
-        let peak_meter = util::gain_to_db(meter.load(std::sync::atomic::Ordering::Relaxed));
-        let peak_meter_text = if peak_meter > util::MINUS_INFINITY_DB {
-            format!("{peak_meter:.1} dBFS")
-        } else {
-            String::from("-inf dBFS")
-        };
+        // The held maximum only ever climbs; it is cleared explicitly via `reset_max_peak`.
+        if peak > max_meter.load(std::sync::atomic::Ordering::Relaxed) {
+            max_meter.store(peak, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
 
-        let peak_meter_normalized = (peak_meter + 60.0) / 60.0;
-        ui.add(egui::widgets::ProgressBar::new(peak_meter_normalized).text(peak_meter_text));
+    /// Store an already-integrated RMS reading (VU / K-system modes). The integrator itself
+    /// provides the ballistics, so the value is stored directly while still latching the held
+    /// maximum.
+    fn store_rms_meter(&self, rms: f32, meter: &Arc<AtomicF32>, max_meter: &Arc<AtomicF32>) {
+        meter.store(rms, std::sync::atomic::Ordering::Relaxed);
+        if rms > max_meter.load(std::sync::atomic::Ordering::Relaxed) {
+            max_meter.store(rms, std::sync::atomic::Ordering::Relaxed);
+        }
     }
 }
 